@@ -33,6 +33,7 @@ extern crate util;
 pub mod protos;
 pub use protos::*;
 mod autoimpl;
+mod private_transaction;
 pub mod router;
 
 use crypto::{CreateKey, KeyPair, Message as SignMessage, PrivKey, PubKey, Sign, Signature, SIGNATURE_BYTES_LEN};
@@ -76,6 +77,128 @@ impl From<RichStatus> for Status {
     }
 }
 
+/// Domain-separation tag for transaction hashes.
+const TAG_TX: &str = "CITA/tx";
+/// Domain-separation tag for block-header hashes.
+const TAG_HEADER: &str = "CITA/header";
+/// Domain-separation tag for proof hashes.
+const TAG_PROOF: &str = "CITA/proof";
+
+/// The first transaction version that hashes with domain separation; older
+/// transactions keep the untagged `crypt_hash` so both formats can coexist.
+const TAGGED_HASH_VERSION: u32 = 1;
+
+/// Compute a domain-separated (tagged) hash in the spirit of the BIP-340 /
+/// BOLT-12 tagged hashes.
+///
+/// The tag is first digested (`tag_digest = crypt_hash(tag)`) and then prepended
+/// twice to the message, so that a byte sequence valid under two message types
+/// can never hash identically: `crypt_hash(tag_digest ++ tag_digest ++ bytes)`.
+fn tagged_crypt_hash(tag: &str, bytes: &[u8]) -> H256 {
+    let tag_digest = tag.as_bytes().crypt_hash().to_vec();
+    let mut msg = Vec::with_capacity(tag_digest.len() * 2 + bytes.len());
+    msg.extend_from_slice(&tag_digest);
+    msg.extend_from_slice(&tag_digest);
+    msg.extend_from_slice(bytes);
+    msg.crypt_hash()
+}
+
+/// Length of the public key embedded in an SM2 signature.
+const PUBKEY_BYTES_LEN: usize = 64;
+/// Length of an SM2 signature: the signer public key followed by `r || s`.
+const SM2_SIGNATURE_BYTES_LEN: usize = PUBKEY_BYTES_LEN + 64;
+
+/// A pluggable signature scheme.
+///
+/// Decouples the crypto primitive used to sign and recover a transaction from
+/// the transaction logic itself, mirroring the ethkey-style abstraction, so
+/// that `build_unverified` and `recover_public` both route through the same
+/// object instead of open-coding the primitive. Recovery is dispatched at run
+/// time by the `Crypto` value carried on the transaction, letting a node verify
+/// blocks that mix secp256k1- and SM2-signed transactions.
+pub trait SignatureScheme {
+    /// The `Crypto` value this scheme corresponds to.
+    fn crypto(&self) -> Crypto;
+
+    /// Sign a message `hash` with `sk`, returning the raw signature bytes.
+    fn sign(&self, sk: &PrivKey, hash: &H256) -> Vec<u8>;
+
+    /// Recover the signer's public key from a transaction `hash` and the raw
+    /// signature bytes.
+    fn recover(&self, hash: &H256, sig: &[u8]) -> Result<PubKey, String>;
+
+    /// The expected signature length for this scheme.
+    fn signer_len(&self) -> usize;
+}
+
+/// The secp256k1 recoverable-signature scheme.
+struct Secp256k1Scheme;
+
+impl SignatureScheme for Secp256k1Scheme {
+    fn crypto(&self) -> Crypto {
+        Crypto::SECP
+    }
+
+    fn sign(&self, sk: &PrivKey, hash: &H256) -> Vec<u8> {
+        Signature::sign(sk, &SignMessage::from(*hash)).unwrap().to_vec()
+    }
+
+    fn recover(&self, hash: &H256, sig: &[u8]) -> Result<PubKey, String> {
+        let signature = Signature::from(sig);
+        signature
+            .recover(hash)
+            .map_err(|_| String::from("Recover error"))
+    }
+
+    fn signer_len(&self) -> usize {
+        SIGNATURE_BYTES_LEN
+    }
+}
+
+/// The SM2 scheme used by CITA deployments.
+///
+/// SM2 is not publicly recoverable, so a CITA SM2 signature carries the signer
+/// public key in its leading `PUBKEY_BYTES_LEN` bytes followed by `r || s`.
+/// Recovery extracts that public key and checks the signature against it.
+struct Sm2Scheme;
+
+impl SignatureScheme for Sm2Scheme {
+    fn crypto(&self) -> Crypto {
+        Crypto::SM2
+    }
+
+    fn sign(&self, sk: &PrivKey, hash: &H256) -> Vec<u8> {
+        Signature::sign(sk, &SignMessage::from(*hash)).unwrap().to_vec()
+    }
+
+    fn recover(&self, hash: &H256, sig: &[u8]) -> Result<PubKey, String> {
+        let pubkey = PubKey::from_slice(&sig[..PUBKEY_BYTES_LEN]);
+        let signature = Signature::from(sig);
+        match signature.verify_public(&pubkey, &SignMessage::from(*hash)) {
+            Ok(true) => Ok(pubkey),
+            _ => Err(String::from("Recover error")),
+        }
+    }
+
+    fn signer_len(&self) -> usize {
+        SM2_SIGNATURE_BYTES_LEN
+    }
+}
+
+/// The signature scheme this node signs with.
+fn native_scheme() -> Secp256k1Scheme {
+    Secp256k1Scheme
+}
+
+/// Return the signature scheme for a `Crypto` value. Every `Crypto` variant
+/// has a scheme, so the result is always `Some`.
+fn signature_scheme(crypto: Crypto) -> Option<Box<SignatureScheme>> {
+    match crypto {
+        Crypto::SECP => Some(Box::new(Secp256k1Scheme)),
+        Crypto::SM2 => Some(Box::new(Sm2Scheme)),
+    }
+}
+
 impl Transaction {
     /// Signs the transaction by PrivKey.
     pub fn sign(&self, sk: PrivKey) -> SignedTransaction {
@@ -96,39 +219,51 @@ impl Transaction {
     pub fn build_unverified(&self, sk: PrivKey) -> UnverifiedTransaction {
         let mut unverified_tx = UnverifiedTransaction::new();
         let bytes: Vec<u8> = self.try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        let hash = if self.get_version() >= TAGGED_HASH_VERSION {
+            tagged_crypt_hash(TAG_TX, &bytes)
+        } else {
+            bytes.crypt_hash()
+        };
         unverified_tx.set_transaction(self.clone());
-        let signature = Signature::sign(&sk, &SignMessage::from(hash)).unwrap();
-        unverified_tx.set_signature(signature.to_vec());
-        unverified_tx.set_crypto(Crypto::SECP);
+        let scheme = native_scheme();
+        unverified_tx.set_signature(scheme.sign(&sk, &hash));
+        unverified_tx.set_crypto(scheme.crypto());
         unverified_tx
     }
+
+    /// Domain-separated hash of this transaction.
+    pub fn tagged_crypt_hash(&self) -> H256 {
+        let bytes: Vec<u8> = self.try_into().unwrap();
+        tagged_crypt_hash(TAG_TX, &bytes)
+    }
 }
 
 impl UnverifiedTransaction {
     /// Try to recover the public key.
     pub fn recover_public(&self) -> Result<(PubKey, H256), (H256, String)> {
         let bytes: Vec<u8> = self.get_transaction().try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        let hash = if self.get_transaction().get_version() >= TAGGED_HASH_VERSION {
+            tagged_crypt_hash(TAG_TX, &bytes)
+        } else {
+            bytes.crypt_hash()
+        };
         let tx_hash = self.crypt_hash();
-        if self.get_signature().len() != SIGNATURE_BYTES_LEN {
+        let scheme = match signature_scheme(self.get_crypto()) {
+            Some(scheme) => scheme,
+            None => {
+                trace!("Unexpected crypto {}", tx_hash);
+                return Err((tx_hash, String::from("Unexpected crypto")));
+            }
+        };
+        if self.get_signature().len() != scheme.signer_len() {
             trace!("Invalid signature length {}", hash);
             Err((tx_hash, String::from("Invalid signature length")))
         } else {
-            match self.get_crypto() {
-                Crypto::SECP => {
-                    let signature = Signature::from(self.get_signature());
-                    match signature.recover(&hash) {
-                        Ok(pubkey) => Ok((pubkey, tx_hash)),
-                        _ => {
-                            trace!("Recover error {}", tx_hash);
-                            Err((tx_hash, String::from("Recover error")))
-                        }
-                    }
-                }
-                _ => {
-                    trace!("Unexpected crypto {}", tx_hash);
-                    Err((tx_hash, String::from("Unexpected crypto")))
+            match scheme.recover(&hash, self.get_signature()) {
+                Ok(pubkey) => Ok((pubkey, tx_hash)),
+                Err(err) => {
+                    trace!("Recover error {}", tx_hash);
+                    Err((tx_hash, err))
                 }
             }
         }
@@ -139,9 +274,21 @@ impl UnverifiedTransaction {
         bytes.crypt_hash()
     }
 
+    /// Domain-separated hash of this transaction.
+    pub fn tagged_crypt_hash(&self) -> H256 {
+        let bytes: Vec<u8> = self.get_transaction().try_into().unwrap();
+        tagged_crypt_hash(TAG_TX, &bytes)
+    }
+
     pub fn tx_verify_req_msg(&self) -> VerifyTxReq {
         let bytes: Vec<u8> = self.get_transaction().try_into().unwrap();
-        let hash = bytes.crypt_hash();
+        // Must match the hash that `build_unverified`/`recover_public` sign
+        // against, so that v1 (tagged) transactions verify in the microservice.
+        let hash = if self.get_transaction().get_version() >= TAGGED_HASH_VERSION {
+            tagged_crypt_hash(TAG_TX, &bytes)
+        } else {
+            bytes.crypt_hash()
+        };
         let mut verify_tx_req = VerifyTxReq::new();
         verify_tx_req.set_valid_until_block(self.get_transaction().get_valid_until_block());
         // tx hash
@@ -184,6 +331,14 @@ impl SignedTransaction {
     }
 }
 
+impl Proof {
+    /// Domain-separated hash of this proof.
+    pub fn tagged_crypt_hash(&self) -> H256 {
+        let bytes: Vec<u8> = self.try_into().unwrap();
+        tagged_crypt_hash(TAG_PROOF, &bytes)
+    }
+}
+
 impl Eq for Proof {}
 
 impl Decodable for Proof {
@@ -240,6 +395,12 @@ impl BlockHeader {
         let bytes: Vec<u8> = self.try_into().unwrap();
         bytes.crypt_hash().to_hex()
     }
+
+    /// Domain-separated hash of this block header.
+    pub fn tagged_crypt_hash(&self) -> H256 {
+        let bytes: Vec<u8> = self.try_into().unwrap();
+        tagged_crypt_hash(TAG_HEADER, &bytes)
+    }
 }
 
 impl BlockBody {
@@ -253,6 +414,61 @@ impl BlockBody {
     pub fn transactions_root(&self) -> H256 {
         merklehash::MerkleTree::from_hashes(self.transaction_hashes().clone()).get_root_hash()
     }
+
+    /// Build the authentication path that proves `tx_hash` is included in this
+    /// body's transactions merkle tree.
+    ///
+    /// The path is ordered bottom-up: for each level it records the sibling
+    /// hash and a flag that is `true` when the node being folded is the left
+    /// child and `false` when it is the right child. Returns `None` when the
+    /// transaction is not part of this body. Feed the result to
+    /// `verify_transaction_proof` together with the committed transactions
+    /// root to check inclusion without the full body.
+    pub fn transaction_proof(&self, tx_hash: H256) -> Option<Vec<(H256, bool)>> {
+        let mut level = self.transaction_hashes();
+        let mut index = level.iter().position(|h| *h == tx_hash)?;
+        let mut proof = Vec::new();
+        while level.len() > 1 {
+            // Promote (duplicate) the last node on an odd-sized level, matching
+            // the convention used by `MerkleTree`.
+            if level.len() % 2 == 1 {
+                let last = level[level.len() - 1];
+                level.push(last);
+            }
+            let is_left = index % 2 == 0;
+            let sibling = if is_left { index + 1 } else { index - 1 };
+            proof.push((level[sibling], is_left));
+            level = level
+                .chunks(2)
+                .map(|pair| merge_hash(pair[0], pair[1]))
+                .collect();
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+/// Combine two child hashes into their parent by hashing `left || right`.
+fn merge_hash(left: H256, right: H256) -> H256 {
+    let mut bytes = left.to_vec();
+    bytes.extend_from_slice(&right.to_vec());
+    bytes.crypt_hash()
+}
+
+/// Verify a transaction inclusion proof produced by
+/// `BlockBody::transaction_proof` against a committed `root`.
+///
+/// Folds `leaf` upward, hashing `crypt_hash(left || right)` in the order
+/// recorded by the proof, and compares the result against `root`.
+pub fn verify_transaction_proof(leaf: H256, proof: &[(H256, bool)], root: H256) -> bool {
+    let folded = proof.iter().fold(leaf, |node, &(sibling, is_left)| {
+        if is_left {
+            merge_hash(node, sibling)
+        } else {
+            merge_hash(sibling, node)
+        }
+    });
+    folded == root
 }
 
 #[cfg(test)]
@@ -279,4 +495,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn dispatch_handles_both_schemes() {
+        use super::{signature_scheme, Crypto};
+        // Both schemes resolve at run time: an SM2 transaction is no longer
+        // rejected as "Unexpected crypto".
+        assert_eq!(signature_scheme(Crypto::SECP).unwrap().crypto(), Crypto::SECP);
+        assert_eq!(signature_scheme(Crypto::SM2).unwrap().crypto(), Crypto::SM2);
+    }
+
+    #[test]
+    fn secp_sign_and_recover() {
+        use super::{CreateKey, KeyPair, Transaction};
+        let keypair = KeyPair::gen_keypair();
+
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1]);
+        tx.set_nonce("0".to_string());
+        tx.set_to("123".to_string());
+        tx.set_valid_until_block(99999);
+        tx.set_quota(999999999);
+
+        let signed_tx = tx.sign(*keypair.privkey());
+        let (pubkey, _) = signed_tx
+            .get_transaction_with_sig()
+            .recover_public()
+            .unwrap();
+        assert_eq!(&pubkey, keypair.pubkey());
+    }
+
+    fn body_with_hashes(hashes: &[u8]) -> super::BlockBody {
+        use super::{BlockBody, SignedTransaction};
+        use protobuf::RepeatedField;
+        let txs: Vec<SignedTransaction> = hashes
+            .iter()
+            .map(|i| {
+                let mut tx = SignedTransaction::new();
+                tx.set_tx_hash(super::H256::from(*i as u64).to_vec());
+                tx
+            })
+            .collect();
+        let mut body = BlockBody::new();
+        body.set_transactions(RepeatedField::from_vec(txs));
+        body
+    }
+
+    #[test]
+    fn transaction_proof_multi() {
+        use super::verify_transaction_proof;
+        let body = body_with_hashes(&[1, 2, 3, 4]);
+        let root = body.transactions_root();
+        for leaf in body.transaction_hashes() {
+            let proof = body.transaction_proof(leaf).unwrap();
+            assert!(verify_transaction_proof(leaf, &proof, root));
+        }
+    }
+
+    #[test]
+    fn transaction_proof_odd() {
+        use super::verify_transaction_proof;
+        // Odd transaction count exercises the last-node promotion path.
+        let body = body_with_hashes(&[7, 8, 9]);
+        let root = body.transactions_root();
+        for leaf in body.transaction_hashes() {
+            let proof = body.transaction_proof(leaf).unwrap();
+            assert!(verify_transaction_proof(leaf, &proof, root));
+        }
+    }
+
+    #[test]
+    fn transaction_proof_single() {
+        use super::verify_transaction_proof;
+        let body = body_with_hashes(&[42]);
+        let root = body.transactions_root();
+        let leaf = body.transaction_hashes()[0];
+        let proof = body.transaction_proof(leaf).unwrap();
+        assert!(verify_transaction_proof(leaf, &proof, root));
+    }
+
 }
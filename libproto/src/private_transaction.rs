@@ -0,0 +1,223 @@
+// CITA
+// Copyright 2016-2018 Cryptape Technologies LLC.
+
+// This program is free software: you can redistribute it
+// and/or modify it under the terms of the GNU General Public
+// License as published by the Free Software Foundation,
+// either version 3 of the License, or (at your option) any
+// later version.
+
+// This program is distributed in the hope that it will be
+// useful, but WITHOUT ANY WARRANTY; without even the implied
+// warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Encrypted private transactions.
+//!
+//! The `PrivateTransaction`, `SignedPrivateTransaction` and `WrappedKey`
+//! messages are defined in `protos/private_transaction.proto`; this module adds
+//! the encryption/decryption behavior on top of the generated types, the same
+//! split that `Transaction` uses (generated struct, hand-written impls here).
+//!
+//! A `PrivateTransaction` wraps an encrypted `Transaction` payload together
+//! with the payload key wrapped for each validator allowed to decrypt it: the
+//! serialized transaction is encrypted once under a fresh symmetric payload
+//! key, which is then ECIES-wrapped per validator. The outer wrapper is signed
+//! over the ciphertext hash, so every node can recover the sender and verify
+//! the wrapper even though the payload stays confidential. A validator
+//! acknowledges a wrapper by returning a `SignedPrivateTransaction`.
+
+use super::{PrivateTransaction, SignedPrivateTransaction, Transaction, WrappedKey};
+use crypto::{aes, ecies, CreateKey, KeyPair, Message as SignMessage, PrivKey, PubKey, Sign, Signature,
+             SIGNATURE_BYTES_LEN};
+use protobuf::RepeatedField;
+use std::convert::{TryFrom, TryInto};
+use types::H256;
+use util::Hashable;
+
+/// Length in bytes of the symmetric payload key.
+const PAYLOAD_KEY_LEN: usize = 32;
+/// Length in bytes of the AES nonce (initialisation vector).
+const NONCE_LEN: usize = 16;
+
+impl Transaction {
+    /// Seal this transaction for `validators`, signing the ciphertext with `sk`.
+    ///
+    /// The serialized transaction is encrypted under a fresh symmetric payload
+    /// key, which is then ECIES-wrapped for each validator so that any one of
+    /// them can recover it, while nodes outside the set see only ciphertext.
+    pub fn seal_private(&self, validators: &[PubKey], sk: PrivKey) -> PrivateTransaction {
+        let plaintext: Vec<u8> = self.try_into().unwrap();
+
+        // Fresh per-transaction symmetric payload key and nonce, drawn from
+        // fresh key material.
+        let payload_key = KeyPair::gen_keypair();
+        let key_bytes = payload_key.privkey()[..PAYLOAD_KEY_LEN].to_vec();
+        let nonce = KeyPair::gen_keypair().privkey()[..NONCE_LEN].to_vec();
+
+        let encrypted = aes::encrypt(&key_bytes, &nonce, &plaintext);
+        let wrapped_keys: Vec<WrappedKey> = validators
+            .iter()
+            .map(|validator| {
+                let mut wrapped = WrappedKey::new();
+                wrapped.set_validator(validator.to_vec());
+                wrapped.set_ciphertext(ecies::encrypt(validator, &key_bytes));
+                wrapped
+            })
+            .collect();
+
+        let signature = Signature::sign(&sk, &SignMessage::from(encrypted.crypt_hash()))
+            .unwrap()
+            .to_vec();
+
+        let mut private = PrivateTransaction::new();
+        private.set_encrypted(encrypted);
+        private.set_nonce(nonce);
+        private.set_wrapped_keys(RepeatedField::from_vec(wrapped_keys));
+        private.set_signature(signature);
+        private
+    }
+}
+
+impl PrivateTransaction {
+    /// Hash of the encrypted payload, which the wrapper signature covers.
+    pub fn crypt_hash(&self) -> H256 {
+        self.get_encrypted().crypt_hash()
+    }
+
+    /// Recover the sender's public key from the signature over the ciphertext.
+    ///
+    /// This keeps the outer wrapper verifiable by every node even when the
+    /// payload cannot be decrypted.
+    pub fn recover_public(&self) -> Result<PubKey, String> {
+        let sig = self.get_signature();
+        if sig.len() != SIGNATURE_BYTES_LEN {
+            return Err(String::from("Invalid signature length"));
+        }
+        let signature = Signature::from(sig);
+        signature
+            .recover(&self.crypt_hash())
+            .map_err(|_| String::from("Recover error"))
+    }
+
+    /// Decrypt and recover the inner transaction as an authorized validator.
+    ///
+    /// Unwraps the symmetric payload key addressed to the validator owning `sk`,
+    /// then decrypts the payload. Returns an error when `sk` is not authorized
+    /// or the payload fails to decode.
+    pub fn open(&self, sk: PrivKey) -> Result<Transaction, String> {
+        let pubkey = *KeyPair::from_privkey(sk)
+            .map_err(|_| String::from("Invalid private key"))?
+            .pubkey();
+        let wrapped = self
+            .get_wrapped_keys()
+            .iter()
+            .find(|w| PubKey::from_slice(w.get_validator()) == pubkey)
+            .ok_or_else(|| String::from("Validator not authorized"))?;
+
+        let key_bytes = ecies::decrypt(&sk, wrapped.get_ciphertext())
+            .map_err(|_| String::from("Unwrap payload key error"))?;
+        let plaintext = aes::decrypt(&key_bytes, self.get_nonce(), self.get_encrypted())
+            .map_err(|_| String::from("Decrypt payload error"))?;
+        Transaction::try_from(plaintext.as_slice())
+            .map_err(|_| String::from("Decode transaction error"))
+    }
+}
+
+impl SignedPrivateTransaction {
+    /// Sign a `PrivateTransaction` wrapper as an acknowledging validator.
+    pub fn sign(private_transaction: PrivateTransaction, sk: PrivKey) -> Self {
+        let hash = private_transaction.crypt_hash();
+        let signature = Signature::sign(&sk, &SignMessage::from(hash)).unwrap().to_vec();
+        let mut signed = SignedPrivateTransaction::new();
+        signed.set_private_transaction(private_transaction);
+        signed.set_signature(signature);
+        signed
+    }
+
+    /// Recover the acknowledging validator's public key.
+    pub fn recover_public(&self) -> Result<PubKey, String> {
+        let sig = self.get_signature();
+        if sig.len() != SIGNATURE_BYTES_LEN {
+            return Err(String::from("Invalid signature length"));
+        }
+        let signature = Signature::from(sig);
+        signature
+            .recover(&self.get_private_transaction().crypt_hash())
+            .map_err(|_| String::from("Recover error"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{PrivateTransaction, SignedPrivateTransaction, Transaction};
+    use crypto::{CreateKey, KeyPair};
+    use protobuf::{self, Message};
+
+    fn sample_tx() -> Transaction {
+        let mut tx = Transaction::new();
+        tx.set_data(vec![1, 2, 3]);
+        tx.set_nonce("0".to_string());
+        tx.set_to("123".to_string());
+        tx.set_valid_until_block(99999);
+        tx.set_quota(999999999);
+        tx
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let sender = KeyPair::gen_keypair();
+        let validator = KeyPair::gen_keypair();
+        let tx = sample_tx();
+
+        let private = tx.seal_private(&[*validator.pubkey()], *sender.privkey());
+        // Outer wrapper stays verifiable: the sender is recoverable by anyone.
+        assert_eq!(private.recover_public().unwrap(), *sender.pubkey());
+
+        // The authorized validator recovers the original transaction.
+        let opened = private.open(*validator.privkey()).unwrap();
+        assert_eq!(opened, tx);
+    }
+
+    #[test]
+    fn open_rejects_unauthorized_validator() {
+        let sender = KeyPair::gen_keypair();
+        let validator = KeyPair::gen_keypair();
+        let outsider = KeyPair::gen_keypair();
+
+        let private = sample_tx().seal_private(&[*validator.pubkey()], *sender.privkey());
+        assert!(private.open(*outsider.privkey()).is_err());
+    }
+
+    #[test]
+    fn signed_private_transaction_recover() {
+        let sender = KeyPair::gen_keypair();
+        let validator = KeyPair::gen_keypair();
+        let private = sample_tx().seal_private(&[*validator.pubkey()], *sender.privkey());
+
+        let signed = SignedPrivateTransaction::sign(private, *validator.privkey());
+        assert_eq!(signed.recover_public().unwrap(), *validator.pubkey());
+    }
+
+    #[test]
+    fn protobuf_wire_round_trip() {
+        // The wrappers are genuine protobuf messages: they serialize to and
+        // parse from the wire like every other message type, so they can be
+        // carried as `Message` envelope content.
+        let sender = KeyPair::gen_keypair();
+        let validator = KeyPair::gen_keypair();
+        let private = sample_tx().seal_private(&[*validator.pubkey()], *sender.privkey());
+
+        let bytes = private.write_to_bytes().unwrap();
+        let decoded = protobuf::parse_from_bytes::<PrivateTransaction>(&bytes).unwrap();
+        assert_eq!(decoded, private);
+
+        let signed = SignedPrivateTransaction::sign(private, *validator.privkey());
+        let bytes = signed.write_to_bytes().unwrap();
+        let decoded = protobuf::parse_from_bytes::<SignedPrivateTransaction>(&bytes).unwrap();
+        assert_eq!(decoded, signed);
+    }
+}
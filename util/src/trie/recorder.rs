@@ -0,0 +1,134 @@
+// Copyright 2015-2017 Parity Technologies (UK) Ltd.
+// This file is part of Parity.
+
+// This software is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This software is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Trie query recorder.
+
+use hashdb::{DBValue, HashDB};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use types::H256;
+
+/// A single recorded trie node, captured while walking a key path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The node hash.
+    pub hash: H256,
+    /// The raw node data.
+    pub data: DBValue,
+    /// The depth at which the node was found, the root being depth 0.
+    pub depth: u32,
+}
+
+/// Records trie nodes as they are visited so that a full node can hand a light
+/// client the Merkle-Patricia proof for a key.
+pub struct Recorder {
+    nodes: Vec<Record>,
+    min_depth: u32,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+impl Recorder {
+    /// Create a new `Recorder` which records all nodes visited.
+    pub fn new() -> Self {
+        Recorder::with_depth(0)
+    }
+
+    /// Create a new `Recorder` which only records nodes at or beyond a given depth.
+    pub fn with_depth(depth: u32) -> Self {
+        Recorder {
+            nodes: Vec::new(),
+            min_depth: depth,
+        }
+    }
+
+    /// Record a visited node at the given depth.
+    pub fn record(&mut self, hash: &H256, data: &[u8], depth: u32) {
+        if depth >= self.min_depth {
+            self.nodes.push(Record {
+                hash: *hash,
+                data: DBValue::from_slice(data),
+                depth: depth,
+            });
+        }
+    }
+
+    /// Drain all recorded nodes, sorted by ascending depth.
+    pub fn drain(&mut self) -> Vec<Record> {
+        self.nodes.sort_by(|a, b| a.depth.cmp(&b.depth));
+        ::std::mem::replace(&mut self.nodes, Vec::new())
+    }
+}
+
+/// A read-only `HashDB` wrapper that records every node fetched from the
+/// backing database into a `Recorder`.
+///
+/// A trie lookup visits nodes by calling `HashDB::get` for each hash on the
+/// key path, so wrapping the backing database and running an ordinary lookup
+/// captures exactly the authentication path needed to prove the result. Fetch
+/// order along a single path equals trie depth, which is recorded as such.
+pub struct RecordingDB<'a> {
+    inner: &'a HashDB,
+    recorder: RefCell<&'a mut Recorder>,
+    depth: Cell<u32>,
+}
+
+impl<'a> RecordingDB<'a> {
+    /// Wrap `inner`, recording fetched nodes into `recorder`.
+    pub fn new(inner: &'a HashDB, recorder: &'a mut Recorder) -> Self {
+        RecordingDB {
+            inner: inner,
+            recorder: RefCell::new(recorder),
+            depth: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> HashDB for RecordingDB<'a> {
+    fn keys(&self) -> HashMap<H256, i32> {
+        self.inner.keys()
+    }
+
+    fn get(&self, key: &H256) -> Option<DBValue> {
+        let value = self.inner.get(key);
+        if let Some(ref data) = value {
+            let depth = self.depth.get();
+            self.recorder.borrow_mut().record(key, data, depth);
+            self.depth.set(depth + 1);
+        }
+        value
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        self.inner.contains(key)
+    }
+
+    fn insert(&mut self, _value: &[u8]) -> H256 {
+        panic!("RecordingDB is read-only");
+    }
+
+    fn emplace(&mut self, _key: H256, _value: DBValue) {
+        panic!("RecordingDB is read-only");
+    }
+
+    fn remove(&mut self, _key: &H256) {
+        panic!("RecordingDB is read-only");
+    }
+}
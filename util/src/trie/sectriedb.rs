@@ -16,16 +16,20 @@
 #![rustfmt_skip]
 
 use super::{Trie, TrieItem, TrieIterator, Query};
+use super::recorder::{Recorder, RecordingDB};
 use super::triedb::TrieDB;
+use memorydb::MemoryDB;
 use types::H256;
 use hashable::Hashable;
-use hashdb::HashDB;
+use hashdb::{DBValue, HashDB};
 
 /// A `Trie` implementation which hashes keys and uses a generic `HashDB` backing database.
 ///
 /// Use it as a `Trie` trait object. You can use `raw()` to get the backing `TrieDB` object.
 pub struct SecTrieDB<'db> {
     raw: TrieDB<'db>,
+    db: &'db HashDB,
+    root: &'db H256,
 }
 
 impl<'db> SecTrieDB<'db> {
@@ -35,7 +39,7 @@ impl<'db> SecTrieDB<'db> {
     /// This guarantees the trie is built correctly.
     /// Returns an error if root does not exist.
     pub fn new(db: &'db HashDB, root: &'db H256) -> super::Result<Self> {
-        Ok(SecTrieDB { raw: TrieDB::new(db, root)? })
+        Ok(SecTrieDB { raw: TrieDB::new(db, root)?, db: db, root: root })
     }
 
     /// Get a reference to the underlying raw `TrieDB` struct.
@@ -47,6 +51,66 @@ impl<'db> SecTrieDB<'db> {
     pub fn raw_mut(&mut self) -> &mut TrieDB<'db> {
         &mut self.raw
     }
+
+    /// Look a key up, recording every trie node visited into `recorder` so the
+    /// caller can hand the resulting Merkle-Patricia proof to a light client.
+    ///
+    /// The key is mangled with `crypt_hash` exactly as in `get_with`. The
+    /// visited nodes can then be obtained from `Recorder::drain`.
+    pub fn get_with_recorder<Q: Query>(
+        &self,
+        key: &[u8],
+        query: Q,
+        recorder: &mut Recorder,
+    ) -> super::Result<Option<Q::Item>> {
+        // Run the lookup over a recording view of the backing database: a trie
+        // lookup fetches each node on the key path via `HashDB::get`, so the
+        // recorder captures exactly the authentication path.
+        let recording = RecordingDB::new(self.db, recorder);
+        let trie = TrieDB::new(&recording, self.root)?;
+        trie.get_with(&key.crypt_hash(), query)
+    }
+}
+
+/// Selects the keying scheme used when constructing a read-only trie over a
+/// backing `HashDB`.
+///
+/// `Mangled` hashes lookup keys via `crypt_hash` (as `SecTrieDB` does), while
+/// `Plain` queries keys verbatim (as `TrieDB` does). This lets the same backing
+/// database be consumed both ways and makes the hashing decision explicit at
+/// construction time rather than baked into the type.
+pub enum TrieFactory {
+    /// Mangle keys with `crypt_hash`.
+    Mangled,
+    /// Use keys verbatim.
+    Plain,
+}
+
+impl TrieFactory {
+    /// Create a read-only `Trie` over `db` rooted at `root`, keyed according to
+    /// this factory's scheme.
+    pub fn readonly<'db>(&self, db: &'db HashDB, root: &'db H256) -> super::Result<Box<Trie + 'db>> {
+        match *self {
+            TrieFactory::Mangled => Ok(Box::new(SecTrieDB::new(db, root)?)),
+            TrieFactory::Plain => Ok(Box::new(TrieDB::new(db, root)?)),
+        }
+    }
+}
+
+/// Verify a Merkle-Patricia proof for `key` against `root`.
+///
+/// The supplied `nodes` (typically the `data` of the `Record`s drained from a
+/// `Recorder`) are loaded into a temporary `MemoryDB` and a normal keyed lookup
+/// is performed, applying the same `crypt_hash` key mangling that `SecTrieDB`
+/// uses. Returns the stored value, `None` when the key is absent from the
+/// proof, or an error if a referenced node is missing from `nodes`.
+pub fn verify_proof(root: H256, key: &[u8], nodes: &[DBValue]) -> super::Result<Option<DBValue>> {
+    let mut memdb = MemoryDB::new();
+    for node in nodes {
+        memdb.insert(node);
+    }
+    let trie = TrieDB::new(&memdb, &root)?;
+    trie.get(&key.crypt_hash())
 }
 
 impl<'db> Trie for SecTrieDB<'db> {
@@ -86,3 +150,36 @@ fn trie_to_sectrie() {
     let t = SecTrieDB::new(&memdb, &root).unwrap();
     assert_eq!(t.get(&[0x01u8, 0x23]).unwrap().unwrap(), DBValue::from_slice(&[0x01u8, 0x23]));
 }
+
+#[test]
+fn recorder_captures_verifiable_proof() {
+    use memorydb::MemoryDB;
+    use hashdb::DBValue;
+    use super::triedbmut::TrieDBMut;
+    use super::super::TrieMut;
+    use super::recorder::Recorder;
+
+    let mut memdb = MemoryDB::new();
+    let mut root = H256::default();
+    {
+        // Insert pre-mangled keys so a `SecTrieDB` lookup of the raw key hits
+        // them; enough entries to build a multi-node trie.
+        let mut t = TrieDBMut::new(&mut memdb, &mut root);
+        for i in 0u8..32 {
+            t.insert(&(&[i]).crypt_hash(), &[i, i]).unwrap();
+        }
+    }
+
+    let t = SecTrieDB::new(&memdb, &root).unwrap();
+    let mut recorder = Recorder::new();
+    let value = t.get_with_recorder(&[5u8], DBValue::from_slice, &mut recorder).unwrap();
+    assert_eq!(value.unwrap(), DBValue::from_slice(&[5u8, 5]));
+
+    // The recorder must actually have captured the visited nodes.
+    let nodes: Vec<DBValue> = recorder.drain().into_iter().map(|r| r.data).collect();
+    assert!(!nodes.is_empty());
+
+    // And those nodes alone must reconstruct the value under the same root.
+    let proved = verify_proof(root, &[5u8], &nodes).unwrap();
+    assert_eq!(proved.unwrap(), DBValue::from_slice(&[5u8, 5]));
+}